@@ -1,16 +1,28 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::{fs, thread};
-use std::fs::{ReadDir};
-use std::io::{Read, Write};
+use std::fs::{File, ReadDir};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::net::{TcpListener, TcpStream};
-use std::sync::Arc;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex};
 use chrono::Local;
 use http::StatusCode;
 
+// Upper bound on a request body accepted via `Content-Length`, so a client-supplied
+// header can't make us allocate an unbounded buffer up front.
+const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
 pub struct Config {
     pub host: String,
     pub root: String,
+    /// Extension (without the leading `.`) to `Content-Type` overrides, consulted
+    /// before the built-in MIME map in `content_type_for`.
+    pub content_types: HashMap<String, String>,
+    /// Number of long-lived worker threads handling connections, bounding how many
+    /// run concurrently. Defaults to the available parallelism (or 4 if unknown).
+    pub workers: usize,
 }
 
 impl Config {
@@ -22,28 +34,120 @@ impl Config {
         Ok(Config {
             host: String::from(args.get(1).unwrap()),
             root: String::from(args.get(2).unwrap()),
+            content_types: HashMap::new(),
+            workers: thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
         })
     }
 }
 
 pub struct Server {
     config: Config,
+    router: Router,
 }
 
-struct Request {
+pub struct Request {
     request_input: TcpStream,
 
-    path: String,
-    method: String,
-    headers: HashMap<String, String>,
+    pub path: String,
+    pub method: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
 
     response_code: u16,
 }
 
+/// Params captured from `:name` and `*name` segments of a matched route pattern.
+pub type Params = HashMap<String, String>;
+
+/// A route handler: given the request and its captured params, writes a response.
+pub type Handler = Box<dyn Fn(&mut Request, &Params) -> Result<(), Box<dyn Error>> + Send + Sync>;
+
+/// Matches request paths against registered `method`/pattern pairs and dispatches to
+/// the corresponding handler. Patterns are split into `/`-separated segments; a segment
+/// starting with `:` captures a single path segment by name, and `*name` captures the
+/// remainder of the path. Falls back to static file serving when nothing matches.
+pub struct Router {
+    routes: Vec<(String, Vec<String>, Handler)>,
+}
+
+enum RouteMatch<'a> {
+    Matched(&'a Handler, Params),
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router { routes: Vec::new() }
+    }
+
+    pub fn add(&mut self, method: &str, pattern: &str, handler: Handler) {
+        self.routes.push((method.to_string(), split_path(pattern), handler));
+    }
+
+    fn find(&self, method: &str, path: &str) -> RouteMatch<'_> {
+        let segments = split_path(path);
+        let mut allowed_methods = Vec::new();
+
+        for (route_method, pattern, handler) in &self.routes {
+            let params = match match_segments(pattern, &segments) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            if route_method == method {
+                return RouteMatch::Matched(handler, params);
+            }
+            allowed_methods.push(route_method.clone());
+        }
+
+        if allowed_methods.is_empty() {
+            RouteMatch::NotFound
+        } else {
+            RouteMatch::MethodNotAllowed(allowed_methods)
+        }
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+fn split_path(path: &str) -> Vec<String> {
+    path.split('/').filter(|segment| !segment.is_empty()).map(String::from).collect()
+}
+
+fn match_segments(pattern: &[String], path: &[String]) -> Option<Params> {
+    let mut params = HashMap::new();
+
+    for (i, segment) in pattern.iter().enumerate() {
+        if let Some(name) = segment.strip_prefix('*') {
+            params.insert(name.to_string(), path[i..].join("/"));
+            return Some(params);
+        }
+
+        let path_segment = path.get(i)?;
+        if let Some(name) = segment.strip_prefix(':') {
+            params.insert(name.to_string(), path_segment.clone());
+        } else if segment != path_segment {
+            return None;
+        }
+    }
+
+    if path.len() != pattern.len() {
+        return None;
+    }
+
+    Some(params)
+}
+
 impl Server {
-    pub fn new(config: Config) -> Server {
+    pub fn new(config: Config, router: Router) -> Server {
         Server {
-            config
+            config,
+            router,
         }
     }
 
@@ -53,36 +157,65 @@ impl Server {
 
         log(&format!("Starting http server on {}:{}", addr.ip(), addr.port()));
 
+        let worker_count = self.config.workers;
+        let (sender, receiver) = mpsc::sync_channel::<TcpStream>(worker_count);
+        let receiver = Arc::new(Mutex::new(receiver));
+
         let arc = Arc::new(self);
-        for stream in listener.incoming() {
+        for _ in 0..worker_count {
             let server = Arc::clone(&arc);
+            let receiver = Arc::clone(&receiver);
             thread::spawn(move || {
-                // log("thread spawned");
-                let mut stream = stream.unwrap();
                 loop {
-                    let mut req = Request {
-                        request_input: stream,
-                        path: String::new(),
-                        method: String::new(),
-                        headers: HashMap::new(),
-                        response_code: 0,
-                    };
-                    if let Err(err) = server.handle_connection(&mut req) {
-                        log(&format!("Got error: {}", err));
-                        return;
-                    }
-                    if req.method == "" {
-                        break;
+                    let stream = receiver.lock().unwrap().recv();
+                    match stream {
+                        Ok(stream) => server.serve(stream),
+                        Err(_) => break,
                     }
-
-                    log(&format!("{:<6} {:<35} {}",
-                                 req.method, req.path, StatusCode::from_u16(req.response_code).unwrap()));
-
-                    stream = req.request_input;
                 }
-                // log("thread died");
             });
         }
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            if let Err(TrySendError::Full(stream)) = sender.try_send(stream) {
+                reject_overloaded(stream);
+            }
+        }
+    }
+
+    // Runs the request/response loop for a single accepted connection on the calling
+    // (worker) thread, handling `Connection: keep-alive` by reusing the stream across
+    // requests until the client closes it or sends an empty request.
+    fn serve(&self, mut stream: TcpStream) {
+        // log("serving connection");
+        loop {
+            let mut req = Request {
+                request_input: stream,
+                path: String::new(),
+                method: String::new(),
+                headers: HashMap::new(),
+                body: Vec::new(),
+                response_code: 0,
+            };
+            if let Err(err) = self.handle_connection(&mut req) {
+                log(&format!("Got error: {}", err));
+                return;
+            }
+            if req.method == "" {
+                break;
+            }
+
+            log(&format!("{:<6} {:<35} {}",
+                         req.method, req.path, StatusCode::from_u16(req.response_code).unwrap()));
+
+            stream = req.request_input;
+        }
+        // log("connection closed");
     }
 
     fn handle_connection(&self, req: &mut Request) -> Result<(), Box<dyn Error>> {
@@ -95,21 +228,106 @@ impl Server {
             return Ok(());
         }
 
+        match self.router.find(&req.method, &req.path) {
+            RouteMatch::Matched(handler, params) => {
+                handler(req, &params)?;
+                return Ok(());
+            }
+            RouteMatch::MethodNotAllowed(methods) => {
+                let allow = methods.join(", ");
+                req.response_with_headers(StatusCode::METHOD_NOT_ALLOWED.as_u16(),
+                                           Vec::from("Method Not Allowed"),
+                                           vec![("Allow".to_string(), allow)])?;
+                return Ok(());
+            }
+            RouteMatch::NotFound => {}
+        }
+
+        if req.method == "POST" || req.method == "PUT" {
+            let target = match resolve_path(&self.config.root, &req.path) {
+                Ok(path) => path,
+                Err(code) => return respond_with_status(req, code),
+            };
+            fs::create_dir_all(target.parent().unwrap_or(Path::new(&self.config.root)))?;
+            fs::write(&target, &req.body)?;
+            req.response(StatusCode::OK.as_u16(), Vec::new())?;
+            return Ok(());
+        }
+
+        if req.method == "DELETE" {
+            let target = match resolve_path(&self.config.root, &req.path) {
+                Ok(path) => path,
+                Err(code) => return respond_with_status(req, code),
+            };
+            match fs::remove_file(&target) {
+                Ok(_) => req.response(StatusCode::OK.as_u16(), Vec::new())?,
+                Err(_) => req.response(StatusCode::NOT_FOUND.as_u16(), Vec::from("Not Found"))?,
+            };
+            return Ok(());
+        }
+
         if !(req.method == "GET") {
             req.response(StatusCode::NOT_IMPLEMENTED.as_u16(), Vec::from("Not Implemented"))?;
             return Ok(());
         }
 
-        match fs::metadata(&req.path) {
+        let mut serve_path = match resolve_path(&self.config.root, &req.path) {
+            Ok(path) => path,
+            Err(code) => return respond_with_status(req, code),
+        };
+
+        if serve_path.is_dir() {
+            let index_html = serve_path.join("index.html");
+            let index_htm = serve_path.join("index.htm");
+            if index_html.is_file() {
+                serve_path = index_html;
+            } else if index_htm.is_file() {
+                serve_path = index_htm;
+            }
+        }
+
+        match fs::metadata(&serve_path) {
             Ok(metadata) => {
                 if metadata.is_dir() {
-                    let dir = fs::read_dir(&req.path).unwrap();
+                    let dir = fs::read_dir(&serve_path).unwrap();
+                    let extra = vec![("Content-Type".to_string(), "text/html".to_string())];
+                    let dir_name = serve_path.to_string_lossy().to_string();
 
-                    req.response(StatusCode::OK.as_u16(), dir_navigation_page(&req.path,dir))?;
+                    req.response_with_headers(StatusCode::OK.as_u16(), dir_navigation_page(&dir_name, dir), extra)?;
                 } else if metadata.is_file() {
-                    match fs::read(&req.path) {
-                        Ok(content) => {
-                            req.response(StatusCode::OK.as_u16(), content)?;
+                    let content_type = content_type_for(&serve_path.to_string_lossy(), &self.config.content_types);
+
+                    match File::open(&serve_path) {
+                        Ok(file) => {
+                            let total = metadata.len();
+
+                            match req.headers.get("Range").cloned() {
+                                Some(range_header) => {
+                                    match parse_range(&range_header, total) {
+                                        Some((start, end)) => {
+                                            let extra = vec![
+                                                ("Content-Range".to_string(), format!("bytes {}-{}/{}", start, end, total)),
+                                                ("Accept-Ranges".to_string(), "bytes".to_string()),
+                                                ("Content-Type".to_string(), content_type),
+                                            ];
+                                            req.response_stream(StatusCode::PARTIAL_CONTENT.as_u16(),
+                                                                 file, start, end - start + 1, extra)?;
+                                        }
+                                        None => {
+                                            let extra = vec![("Content-Range".to_string(), format!("bytes */{}", total))];
+                                            req.response_with_headers(StatusCode::RANGE_NOT_SATISFIABLE.as_u16(),
+                                                                       Vec::new(), extra)?;
+                                        }
+                                    }
+                                }
+                                None => {
+                                    let extra = vec![
+                                        ("Accept-Ranges".to_string(), "bytes".to_string()),
+                                        ("Content-Type".to_string(), content_type),
+                                    ];
+                                    req.response_stream(StatusCode::OK.as_u16(), file, 0, total, extra)?;
+                                }
+                            }
                         }
                         Err(_) => {
                             req.response(StatusCode::NOT_FOUND.as_u16(), Vec::from("Not Found"))?;
@@ -127,6 +345,135 @@ impl Server {
     }
 }
 
+// Writes a response carrying just a bare status line/reason phrase as the body, used
+// for the confinement errors `resolve_path` returns (403/404).
+fn respond_with_status(req: &mut Request, code: u16) -> Result<(), Box<dyn Error>> {
+    req.response(code, Vec::from(StatusCode::from_u16(code)?.canonical_reason().unwrap_or("")))
+}
+
+// Maps the extension of `path` to a `Content-Type`, checking `overrides` (see
+// `Config::content_types`) before the built-in table, and finally defaulting to
+// `application/octet-stream`.
+fn content_type_for(path: &str, overrides: &HashMap<String, String>) -> String {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    if let Some(content_type) = overrides.get(&extension) {
+        return content_type.clone();
+    }
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "txt" => "text/plain",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }.to_string()
+}
+
+// Resolves a URL-decoded, `root`-confined filesystem path for `request_path`. Percent
+// escapes (`%20` etc.) are decoded first, then the result is joined onto the
+// canonicalized `root` and normalized lexically (collapsing `.`/`..` components)
+// rather than via `fs::canonicalize`, since callers like the key-value store
+// (`POST`/`PUT`/`DELETE`) need to address files that don't exist yet. Anything that
+// normalizes outside of `root` (e.g. `/../../etc/passwd`) is rejected with `403`; a
+// missing `root` itself is rejected with `404`. Used for every handler that touches
+// the filesystem under `root`, so a new one doesn't reopen this traversal hole.
+fn resolve_path(root: &str, request_path: &str) -> Result<PathBuf, u16> {
+    let root = fs::canonicalize(root).map_err(|_| StatusCode::NOT_FOUND.as_u16())?;
+
+    let decoded = percent_decode(request_path);
+    let joined = root.join(decoded.trim_start_matches('/'));
+    let normalized = normalize_components(&joined);
+
+    if !normalized.starts_with(&root) {
+        return Err(StatusCode::FORBIDDEN.as_u16());
+    }
+
+    // The lexical normalization above only rejects `..` segments that try to climb out
+    // of `root` textually; it says nothing about symlinks already sitting inside `root`
+    // that point somewhere else entirely. So walk the remaining path component by
+    // component, canonicalizing (and re-checking confinement on) the deepest ancestor
+    // that actually exists on disk - that resolves every symlink along the way - then
+    // lexically re-append whatever suffix doesn't exist yet (a new key-value store entry
+    // has nothing on disk left to resolve, so there's nothing left to escape through).
+    let relative = normalized.strip_prefix(&root).map_err(|_| StatusCode::FORBIDDEN.as_u16())?;
+    let mut existing = root.clone();
+    let mut suffix = PathBuf::new();
+    let mut still_exists = true;
+
+    for component in relative.components() {
+        if still_exists {
+            let candidate = existing.join(component.as_os_str());
+            if fs::symlink_metadata(&candidate).is_ok() {
+                existing = candidate;
+                continue;
+            }
+            still_exists = false;
+        }
+        suffix.push(component.as_os_str());
+    }
+
+    let existing = fs::canonicalize(&existing).map_err(|_| StatusCode::NOT_FOUND.as_u16())?;
+    if !existing.starts_with(&root) {
+        return Err(StatusCode::FORBIDDEN.as_u16());
+    }
+
+    Ok(existing.join(suffix))
+}
+
+// Lexically collapses `.`/`..` path components without touching the filesystem, so
+// `resolve_path` can confine paths that don't exist yet (new key-value store entries).
+fn normalize_components(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => { result.pop(); }
+            Component::CurDir => {}
+            other => result.push(other.as_os_str()),
+        }
+    }
+    result
+}
+
+// Decodes `%XX` percent-escapes (e.g. `%20` -> a space). Invalid or truncated escapes
+// are passed through unchanged.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            match hex {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                    continue;
+                }
+                None => {}
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
 fn dir_navigation_page(dir_name: &String, dir: ReadDir) -> Vec<u8> {
     let mut file_list = String::new();
     dir.into_iter().for_each(|f| {
@@ -138,17 +485,51 @@ fn dir_navigation_page(dir_name: &String, dir: ReadDir) -> Vec<u8> {
     format!("<html><body><h1>{}</h1><br>{}</body></html>", dir_name, file_list).into_bytes()
 }
 
+// Parses a `Range: bytes=START-END` header against the total file size, handling the
+// open-ended (`START-`) and suffix (`-SUFFIX`) forms. Returns `None` when the range is
+// malformed or unsatisfiable (start at or past the end of the file).
+fn parse_range(header: &str, total: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    let mut parts = spec.splitn(2, '-');
+    let start_str = parts.next()?;
+    let end_str = parts.next()?;
+
+    let (start, end) = if start_str.is_empty() {
+        let suffix_len: u64 = end_str.parse().ok()?;
+        if suffix_len >= total {
+            (0, total.checked_sub(1)?)
+        } else {
+            (total - suffix_len, total - 1)
+        }
+    } else if end_str.is_empty() {
+        (start_str.parse().ok()?, total.checked_sub(1)?)
+    } else {
+        (start_str.parse().ok()?, end_str.parse().ok()?)
+    };
+
+    if start >= total || start > end {
+        return None;
+    }
+
+    Some((start, end.min(total - 1)))
+}
+
 impl Request {
     fn parse_request(&mut self) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0; 1024];
-
-        self.request_input.read(&mut buffer)?;
+        let mut raw = [0; 1024];
 
-        let buffer = String::from_utf8(Vec::from(buffer))?;
-        if buffer == "" {
+        let n = self.request_input.read(&mut raw)?;
+        let raw = &raw[..n];
+        if raw.is_empty() {
             return Ok(());
         }
 
+        let (header_bytes, mut body) = match find_subslice(raw, b"\r\n\r\n") {
+            Some(pos) => (&raw[..pos], raw[pos + 4..].to_vec()),
+            None => (raw, Vec::new()),
+        };
+
+        let buffer = String::from_utf8(Vec::from(header_bytes))?;
         let mut lines = buffer.lines();
 
         if let Some(first_line) = lines.next() {
@@ -174,30 +555,38 @@ impl Request {
                                 String::from(parts[1]).trim().to_string());
         }
 
-        Ok(())
-    }
-
-    fn response(&mut self, code: u16, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
-        let mut headers = HashMap::new();
-
-        headers.insert("Connection", "keep-alive");
-
-        // let len = data.len().to_string();
-        // headers.insert("Content-Length", len.as_str());
-        headers.insert("Transfer-Encoding", "chunked");
+        if let Some(content_length) = self.headers.get("Content-Length") {
+            let content_length: usize = content_length.trim().parse().unwrap_or(0);
+            if content_length > MAX_BODY_SIZE {
+                self.response(StatusCode::PAYLOAD_TOO_LARGE.as_u16(), Vec::new())?;
+                self.method.clear();
+                return Ok(());
+            }
 
-        let headers = headers.iter().
-            fold(String::new(),
-                 |s, kv| {
-                     format!("{}\n{}: {}", s, kv.0, kv.1)
-                 });
+            const READ_CHUNK: usize = 8192;
+            while body.len() < content_length {
+                let to_read = (content_length - body.len()).min(READ_CHUNK);
+                let mut chunk = vec![0; to_read];
+                let read = self.request_input.read(&mut chunk)?;
+                if read == 0 {
+                    break;
+                }
+                chunk.truncate(read);
+                body.extend_from_slice(&chunk);
+            }
+        }
+        self.body = body;
 
-        let response = Vec::from(format!("HTTP/1.1 {}{}\n\n",
-                                         StatusCode::from_u16(code)?,
-                                         headers));
+        Ok(())
+    }
 
-        self.request_input.write(&response)?;
+    pub fn response(&mut self, code: u16, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        self.response_with_headers(code, data, Vec::new())
+    }
 
+    pub fn response_with_headers(&mut self, code: u16, data: Vec<u8>,
+                              extra_headers: Vec<(String, String)>) -> Result<(), Box<dyn Error>> {
+        self.write_status_line(code, &extra_headers)?;
 
         const BYTES_PER_CHUNK: usize = 500;
 
@@ -216,10 +605,38 @@ impl Request {
 
             let offset = chunk_num * BYTES_PER_CHUNK;
             let chunk = &data[offset..offset + in_chunk_bytes];
-            self.request_input.write(format!("{:x}", in_chunk_bytes).to_string().as_bytes())?;
-            self.request_input.write(b"\r\n")?;
-            self.request_input.write(chunk)?;
-            self.request_input.write(b"\r\n")?;
+            self.write_chunk(chunk)?;
+        }
+        self.request_input.write(b"0\r\n\r\n")?;
+        self.request_input.flush()?;
+
+        self.response_code = code;
+
+        Ok(())
+    }
+
+    // Streams `length` bytes starting at `start` directly from `file`, instead of
+    // buffering the whole response in memory first. Used for both plain file GETs
+    // and `Range` requests.
+    fn response_stream(&mut self, code: u16, mut file: File, start: u64, length: u64,
+                        extra_headers: Vec<(String, String)>) -> Result<(), Box<dyn Error>> {
+        file.seek(SeekFrom::Start(start))?;
+
+        self.write_status_line(code, &extra_headers)?;
+
+        const BYTES_PER_CHUNK: usize = 500;
+        let mut remaining = length;
+        let mut buffer = [0u8; BYTES_PER_CHUNK];
+
+        while remaining > 0 {
+            let to_read = remaining.min(BYTES_PER_CHUNK as u64) as usize;
+            let read = file.read(&mut buffer[..to_read])?;
+            if read == 0 {
+                break;
+            }
+
+            self.write_chunk(&buffer[..read])?;
+            remaining -= read as u64;
         }
         self.request_input.write(b"0\r\n\r\n")?;
         self.request_input.flush()?;
@@ -228,9 +645,140 @@ impl Request {
 
         Ok(())
     }
+
+    fn write_status_line(&mut self, code: u16, extra_headers: &Vec<(String, String)>) -> Result<(), Box<dyn Error>> {
+        let mut headers = HashMap::new();
+
+        headers.insert("Connection".to_string(), "keep-alive".to_string());
+
+        // let len = data.len().to_string();
+        // headers.insert("Content-Length", len.as_str());
+        headers.insert("Transfer-Encoding".to_string(), "chunked".to_string());
+
+        for (key, value) in extra_headers {
+            headers.insert(key.clone(), value.clone());
+        }
+
+        let headers = headers.iter().
+            fold(String::new(),
+                 |s, kv| {
+                     format!("{}\n{}: {}", s, kv.0, kv.1)
+                 });
+
+        let response = Vec::from(format!("HTTP/1.1 {}{}\n\n",
+                                         StatusCode::from_u16(code)?,
+                                         headers));
+
+        self.request_input.write(&response)?;
+
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.request_input.write(format!("{:x}", chunk.len()).as_bytes())?;
+        self.request_input.write(b"\r\n")?;
+        self.request_input.write(chunk)?;
+        self.request_input.write(b"\r\n")?;
+
+        Ok(())
+    }
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// Responds with a bare `503 Service Unavailable` and closes the socket, used when the
+// worker pool's queue is full so the accept loop never blocks on a busy server.
+fn reject_overloaded(mut stream: TcpStream) {
+    let _ = stream.write_all(
+        format!("HTTP/1.1 {}\r\nConnection: close\r\n\r\n", StatusCode::SERVICE_UNAVAILABLE).as_bytes());
+    let _ = stream.flush();
 }
 
 fn log(message: &str) {
     let date = Local::now();
     println!("[{}]: {}", date.format("%H:%M:%S"), message);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a throwaway root directory under the OS temp dir, unique per test, so
+    // concurrent `cargo test` runs don't trip over each other's fixtures.
+    fn test_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("resolve_path_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rejects_dot_dot_traversal() {
+        let root = test_root("dotdot");
+        fs::write(root.join("secret.txt"), b"inside").unwrap();
+
+        let err = resolve_path(root.to_str().unwrap(), "/../secret.txt").unwrap_err();
+        assert_eq!(err, StatusCode::FORBIDDEN.as_u16());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rejects_percent_encoded_traversal() {
+        let root = test_root("percent");
+        fs::write(root.join("secret.txt"), b"inside").unwrap();
+
+        // "%2e%2e" decodes to "..".
+        let err = resolve_path(root.to_str().unwrap(), "/%2e%2e/secret.txt").unwrap_err();
+        assert_eq!(err, StatusCode::FORBIDDEN.as_u16());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rejects_symlink_escape_for_existing_target() {
+        let base = test_root("symlink_existing_base");
+        let root = base.join("root");
+        let outside = base.join("outside");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret.txt"), b"outside").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+
+        let err = resolve_path(root.to_str().unwrap(), "/escape/secret.txt").unwrap_err();
+        assert_eq!(err, StatusCode::FORBIDDEN.as_u16());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn allows_confined_new_target() {
+        let root = test_root("confined_new");
+
+        let resolved = resolve_path(root.to_str().unwrap(), "/new_key.txt").unwrap();
+        assert_eq!(resolved, fs::canonicalize(&root).unwrap().join("new_key.txt"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    // Mirrors the key-value store's PUT path: the final segment doesn't exist yet, so
+    // there's nothing for `resolve_path` to canonicalize directly, but its parent is a
+    // symlink pointing outside `root` and must still be caught.
+    #[test]
+    fn rejects_symlink_escape_for_new_kv_entry() {
+        let base = test_root("symlink_new_kv_base");
+        let root = base.join("root");
+        let outside = base.join("outside");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+
+        let err = resolve_path(root.to_str().unwrap(), "/escape/newfile.txt").unwrap_err();
+        assert_eq!(err, StatusCode::FORBIDDEN.as_u16());
+        assert!(!outside.join("newfile.txt").exists());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}